@@ -0,0 +1,394 @@
+use lex::{expect, skip_space, span, take_while, LexErrorKind, LexResult, LexWith};
+use scheme::{lex_identifier_segment, Field, FunctionCall, Scheme, Span, Spanned};
+use types::{GetType, Type};
+
+#[derive(Clone)]
+pub(crate) enum Value<'s> {
+    Field(Field<'s>),
+    FunctionCall(FunctionCall<'s>),
+    Bytes(Vec<u8>),
+    Unsigned(u64),
+    Bool(bool),
+}
+
+impl<'s> GetType for Value<'s> {
+    fn get_type(&self) -> Type {
+        match self {
+            Value::Field(field) => field.get_type(),
+            Value::FunctionCall(call) => call.get_type(),
+            Value::Bytes(_) => Type::Bytes,
+            Value::Unsigned(_) => Type::Unsigned,
+            Value::Bool(_) => Type::Bool,
+        }
+    }
+}
+
+fn lex_bytes_literal(input: &str) -> LexResult<Vec<u8>> {
+    let mut rest = expect(input, "\"")?;
+    let mut bytes = Vec::new();
+
+    loop {
+        match rest.chars().next() {
+            None => return Err((LexErrorKind::ExpectedLiteral("closing `\"`"), rest)),
+            Some('"') => {
+                rest = &rest[1..];
+                break;
+            }
+            Some('\\') => {
+                let mut chars = rest[1..].chars();
+                match chars.next() {
+                    Some('"') => bytes.push(b'"'),
+                    Some('\\') => bytes.push(b'\\'),
+                    Some('n') => bytes.push(b'\n'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('r') => bytes.push(b'\r'),
+                    _ => return Err((LexErrorKind::ExpectedLiteral("escape sequence"), rest)),
+                }
+                rest = chars.as_str();
+            }
+            Some(c) => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    Ok((bytes, rest))
+}
+
+fn lex_unsigned_literal(input: &str) -> LexResult<u64> {
+    let (digits, rest) = take_while(input, "digit", |c| c.is_ascii_digit())?;
+    let value = digits
+        .parse()
+        .map_err(|_| (LexErrorKind::ExpectedName("digit"), input))?;
+    Ok((value, rest))
+}
+
+fn lex_identifier_value<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Value<'s>> {
+    let (segment, rest) = lex_identifier_segment(input)?;
+
+    match segment {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        _ if expect(rest, "(").is_ok() => {
+            let (call, rest) = FunctionCall::lex_with(input, scheme)?;
+            Ok((Value::FunctionCall(call), rest))
+        }
+        _ => {
+            let (field, rest) = Field::lex_with(input, scheme)?;
+            Ok((Value::Field(field), rest))
+        }
+    }
+}
+
+impl<'i, 's> LexWith<'i, &'s Scheme> for Value<'s> {
+    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+        if input.starts_with('"') {
+            let (bytes, rest) = lex_bytes_literal(input)?;
+            return Ok((Value::Bytes(bytes), rest));
+        }
+
+        if input.starts_with(|c: char| c.is_ascii_digit()) {
+            let (n, rest) = lex_unsigned_literal(input)?;
+            return Ok((Value::Unsigned(n), rest));
+        }
+
+        lex_identifier_value(input, scheme)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ComparisonOp {
+    Equal,
+    NotEqual,
+    Contains,
+    GreaterThanOrEqual,
+    GreaterThan,
+    LessThanOrEqual,
+    LessThan,
+}
+
+fn lex_comparison_op(input: &str) -> LexResult<ComparisonOp> {
+    let tokens: &[(&str, ComparisonOp)] = &[
+        ("==", ComparisonOp::Equal),
+        ("!=", ComparisonOp::NotEqual),
+        ("contains", ComparisonOp::Contains),
+        (">=", ComparisonOp::GreaterThanOrEqual),
+        (">", ComparisonOp::GreaterThan),
+        ("<=", ComparisonOp::LessThanOrEqual),
+        ("<", ComparisonOp::LessThan),
+    ];
+
+    for (token, op) in tokens {
+        if let Ok(rest) = expect(input, token) {
+            return Ok((*op, rest));
+        }
+    }
+
+    Err((LexErrorKind::ExpectedLiteral("comparison operator"), input))
+}
+
+pub(crate) struct Comparison<'s> {
+    lhs: Value<'s>,
+    op: ComparisonOp,
+    rhs: Value<'s>,
+}
+
+impl<'s> Comparison<'s> {
+    fn new(lhs: Value<'s>, op: ComparisonOp, rhs: Value<'s>) -> Result<Self, LexErrorKind> {
+        let lhs_type = lhs.get_type();
+
+        if op == ComparisonOp::Contains {
+            if lhs_type != Type::Bytes {
+                return Err(LexErrorKind::TypeMismatch {
+                    expected: Type::Bytes,
+                    actual: lhs_type,
+                });
+            }
+        } else {
+            let rhs_type = rhs.get_type();
+            if lhs_type != rhs_type {
+                return Err(LexErrorKind::TypeMismatch {
+                    expected: lhs_type,
+                    actual: rhs_type,
+                });
+            }
+        }
+
+        Ok(Comparison { lhs, op, rhs })
+    }
+}
+
+pub(crate) enum SimpleExprKind<'s> {
+    Comparison(Comparison<'s>),
+    Bare(Value<'s>),
+}
+
+fn lex_simple_expr<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, SimpleExprKind<'s>> {
+    let (lhs, rest) = Value::lex_with(input, scheme)?;
+    let trimmed = skip_space(rest);
+
+    match lex_comparison_op(trimmed) {
+        Ok((op, rest)) => {
+            let rest = skip_space(rest);
+            let value_start = rest;
+            let (rhs, rest) = Value::lex_with(rest, scheme)?;
+
+            let comparison = Comparison::new(lhs, op, rhs)
+                .map_err(|kind| (kind, span(value_start, rest)))?;
+
+            Ok((SimpleExprKind::Comparison(comparison), rest))
+        }
+        Err(_) => {
+            let lhs_type = lhs.get_type();
+            if lhs_type != Type::Bool {
+                return Err((
+                    LexErrorKind::TypeMismatch {
+                        expected: Type::Bool,
+                        actual: lhs_type,
+                    },
+                    span(input, rest),
+                ));
+            }
+
+            Ok((SimpleExprKind::Bare(lhs), rest))
+        }
+    }
+}
+
+pub(crate) enum FilterKind<'s> {
+    Simple(SimpleExprKind<'s>),
+    Not(Box<Filter<'s>>),
+    And(Box<Filter<'s>>, Box<Filter<'s>>),
+    Or(Box<Filter<'s>>, Box<Filter<'s>>),
+}
+
+pub struct Filter<'s> {
+    kind: FilterKind<'s>,
+    span: Span,
+}
+
+impl<'s> Spanned for Filter<'s> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl<'s> Filter<'s> {
+    pub fn children(&self) -> Vec<&Filter<'s>> {
+        match &self.kind {
+            FilterKind::Simple(_) => Vec::new(),
+            FilterKind::Not(inner) => vec![inner.as_ref()],
+            FilterKind::And(lhs, rhs) | FilterKind::Or(lhs, rhs) => {
+                vec![lhs.as_ref(), rhs.as_ref()]
+            }
+        }
+    }
+}
+
+fn lex_primary<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Filter<'s>> {
+    let initial_input = input;
+
+    match expect(input, "(") {
+        Ok(rest) => {
+            let rest = skip_space(rest);
+            let (inner, rest) = lex_or(rest, scheme)?;
+            let rest = skip_space(rest);
+            let rest = expect(rest, ")")?;
+
+            Ok((
+                Filter {
+                    kind: inner.kind,
+                    span: Span::new(initial_input, span(initial_input, rest)),
+                },
+                rest,
+            ))
+        }
+        Err(_) => {
+            let (simple, rest) = lex_simple_expr(input, scheme)?;
+
+            Ok((
+                Filter {
+                    kind: FilterKind::Simple(simple),
+                    span: Span::new(initial_input, span(initial_input, rest)),
+                },
+                rest,
+            ))
+        }
+    }
+}
+
+fn lex_unary<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Filter<'s>> {
+    let initial_input = input;
+
+    match expect(input, "not") {
+        Ok(rest) => {
+            let rest = skip_space(rest);
+            let (inner, rest) = lex_unary(rest, scheme)?;
+
+            Ok((
+                Filter {
+                    kind: FilterKind::Not(Box::new(inner)),
+                    span: Span::new(initial_input, span(initial_input, rest)),
+                },
+                rest,
+            ))
+        }
+        Err(_) => lex_primary(input, scheme),
+    }
+}
+
+fn lex_and<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Filter<'s>> {
+    let initial_input = input;
+    let (mut lhs, mut rest) = lex_unary(input, scheme)?;
+
+    loop {
+        let trimmed = skip_space(rest);
+        match expect(trimmed, "and") {
+            Ok(after) => {
+                let after = skip_space(after);
+                let (rhs, next_rest) = lex_unary(after, scheme)?;
+                lhs = Filter {
+                    kind: FilterKind::And(Box::new(lhs), Box::new(rhs)),
+                    span: Span::new(initial_input, span(initial_input, next_rest)),
+                };
+                rest = next_rest;
+            }
+            Err(_) => break,
+        }
+    }
+
+    Ok((lhs, rest))
+}
+
+fn lex_or<'i, 's>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Filter<'s>> {
+    let initial_input = input;
+    let (mut lhs, mut rest) = lex_and(input, scheme)?;
+
+    loop {
+        let trimmed = skip_space(rest);
+        match expect(trimmed, "or") {
+            Ok(after) => {
+                let after = skip_space(after);
+                let (rhs, next_rest) = lex_and(after, scheme)?;
+                lhs = Filter {
+                    kind: FilterKind::Or(Box::new(lhs), Box::new(rhs)),
+                    span: Span::new(initial_input, span(initial_input, next_rest)),
+                };
+                rest = next_rest;
+            }
+            Err(_) => break,
+        }
+    }
+
+    Ok((lhs, rest))
+}
+
+impl<'i, 's> LexWith<'i, &'s Scheme> for Filter<'s> {
+    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+        lex_or(input, scheme)
+    }
+}
+
+#[test]
+fn test_comparison() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("http.host".to_owned(), Type::Bytes);
+
+    let (filter, rest) = Filter::lex_with("http.host contains \"admin\";", &scheme).unwrap();
+    assert_eq!(rest, ";");
+    assert!(filter.children().is_empty());
+}
+
+#[test]
+fn test_function_call_in_filter() {
+    use scheme::Signature;
+
+    let mut scheme = Scheme::default();
+    scheme.add_field("http.host".to_owned(), Type::Bytes);
+    scheme.add_function(
+        "lower".to_owned(),
+        Signature::new(vec![Type::Bytes], Type::Bytes),
+    );
+
+    let (filter, rest) =
+        Filter::lex_with("lower(http.host) contains \"admin\"", &scheme).unwrap();
+    assert_eq!(rest, "");
+    assert!(filter.children().is_empty());
+}
+
+#[test]
+fn test_and_or_not() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("is_TCP".to_owned(), Type::Bool);
+    scheme.add_field("is_UDP".to_owned(), Type::Bool);
+
+    let (filter, rest) = Filter::lex_with("is_TCP and not is_UDP or is_UDP", &scheme).unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(filter.children().len(), 2);
+}
+
+#[test]
+fn test_parens() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("is_TCP".to_owned(), Type::Bool);
+    scheme.add_field("is_UDP".to_owned(), Type::Bool);
+
+    let (filter, rest) = Filter::lex_with("(is_TCP or is_UDP) and is_TCP", &scheme).unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(filter.children().len(), 2);
+}
+
+#[test]
+fn test_filter_span() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("is_TCP".to_owned(), Type::Bool);
+
+    let (filter, _) = Filter::lex_with("is_TCP", &scheme).unwrap();
+    assert_eq!(filter.span(), Span { start: 0, len: 6 });
+
+    let (filter, _) = Filter::lex_with("not is_TCP", &scheme).unwrap();
+    assert_eq!(filter.span(), Span { start: 0, len: 10 });
+    assert_eq!(filter.children()[0].span(), Span { start: 4, len: 6 });
+}
@@ -0,0 +1,77 @@
+use scheme::{UnknownFieldError, UnknownFunctionError};
+use std::fmt::{self, Display, Formatter};
+use types::Type;
+
+#[derive(Debug, PartialEq, Fail)]
+pub enum LexErrorKind {
+    #[fail(display = "expected {}", _0)]
+    ExpectedName(&'static str),
+
+    #[fail(display = "expected {:?}", _0)]
+    ExpectedLiteral(&'static str),
+
+    #[fail(display = "{}", _0)]
+    UnknownField(UnknownFieldError),
+
+    #[fail(display = "{}", _0)]
+    UnknownFunction(UnknownFunctionError),
+
+    #[fail(display = "expected value of type {:?}, found {:?}", expected, actual)]
+    TypeMismatch { expected: Type, actual: Type },
+
+    #[fail(display = "expected {} argument(s), found {}", expected, actual)]
+    ArityMismatch { expected: usize, actual: usize },
+}
+
+pub type LexResult<'i, T> = Result<(T, &'i str), (LexErrorKind, &'i str)>;
+
+pub trait LexWith<'i, C>: Sized {
+    fn lex_with(input: &'i str, context: C) -> LexResult<'i, Self>;
+}
+
+pub(crate) fn span<'i>(initial_input: &'i str, input: &'i str) -> &'i str {
+    &initial_input[..initial_input.len() - input.len()]
+}
+
+pub(crate) fn take_while<'i>(
+    input: &'i str,
+    name: &'static str,
+    pred: impl Fn(char) -> bool,
+) -> LexResult<'i, &'i str> {
+    let end = input
+        .char_indices()
+        .find(|&(_, c)| !pred(c))
+        .map_or(input.len(), |(idx, _)| idx);
+
+    if end == 0 {
+        return Err((LexErrorKind::ExpectedName(name), input));
+    }
+
+    Ok((&input[..end], &input[end..]))
+}
+
+pub(crate) fn expect<'i>(
+    input: &'i str,
+    expected: &'static str,
+) -> Result<&'i str, (LexErrorKind, &'i str)> {
+    if input.starts_with(expected) {
+        Ok(&input[expected.len()..])
+    } else {
+        Err((LexErrorKind::ExpectedLiteral(expected), input))
+    }
+}
+
+pub(crate) fn skip_space(input: &str) -> &str {
+    input.trim_start_matches(char::is_whitespace)
+}
+
+pub(crate) fn complete<'i, T>(result: LexResult<'i, T>) -> Result<T, (LexErrorKind, &'i str)> {
+    let (value, rest) = result?;
+    let rest = rest.trim_start();
+
+    if rest.is_empty() {
+        Ok(value)
+    } else {
+        Err((LexErrorKind::ExpectedLiteral("end of input"), rest))
+    }
+}
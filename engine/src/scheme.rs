@@ -1,8 +1,11 @@
-use ast::Filter;
+use ast::{Filter, Value};
 use fnv::FnvBuildHasher;
 use indexmap::map::{Entry, IndexMap};
-use lex::{complete, expect, span, take_while, LexErrorKind, LexResult, LexWith};
+use lex::{complete, expect, span, LexErrorKind, LexResult, LexWith};
+#[cfg(not(feature = "unicode"))]
+use lex::take_while;
 use std::{
+    borrow::Cow,
     cmp::{max, min},
     error::Error,
     fmt::{self, Debug, Display, Formatter},
@@ -11,13 +14,45 @@ use std::{
     ptr,
 };
 use types::{GetType, Type};
+#[cfg(feature = "unicode")]
+use unicode_normalization::UnicodeNormalization;
+#[cfg(feature = "unicode")]
+use unicode_xid::UnicodeXID;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub(crate) fn new(initial_input: &str, matched: &str) -> Self {
+        Span {
+            start: matched.as_ptr() as usize - initial_input.as_ptr() as usize,
+            len: matched.len(),
+        }
+    }
+}
+
+pub trait Spanned {
+    fn span(&self) -> Span;
+}
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(Clone, Copy)]
 pub(crate) struct Field<'s> {
     scheme: &'s Scheme,
     index: usize,
+    span: Span,
 }
 
+impl<'s> PartialEq for Field<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        self.scheme == other.scheme && self.index == other.index
+    }
+}
+
+impl<'s> Eq for Field<'s> {}
+
 impl<'s> Debug for Field<'s> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{}", self.name())
@@ -30,14 +65,47 @@ impl<'s> Hash for Field<'s> {
     }
 }
 
+impl<'s> Spanned for Field<'s> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[cfg(feature = "unicode")]
+fn take_unicode_identifier(input: &str) -> LexResult<&str> {
+    let mut chars = input.char_indices();
+
+    match chars.next() {
+        Some((_, c)) if c == '_' || UnicodeXID::is_xid_start(c) => {}
+        _ => return Err((LexErrorKind::ExpectedName("identifier character"), input)),
+    }
+
+    let end = chars
+        .find(|&(_, c)| !UnicodeXID::is_xid_continue(c))
+        .map_or(input.len(), |(idx, _)| idx);
+
+    Ok((&input[..end], &input[end..]))
+}
+
+pub(crate) fn lex_identifier_segment(input: &str) -> LexResult<&str> {
+    #[cfg(feature = "unicode")]
+    {
+        take_unicode_identifier(input)
+    }
+    #[cfg(not(feature = "unicode"))]
+    {
+        take_while(input, "identifier character", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })
+    }
+}
+
 impl<'i, 's> LexWith<'i, &'s Scheme> for Field<'s> {
     fn lex_with(mut input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
         let initial_input = input;
 
         loop {
-            input = take_while(input, "identifier character", |c| {
-                c.is_ascii_alphanumeric() || c == '_'
-            })?.1;
+            input = lex_identifier_segment(input)?.1;
 
             match expect(input, ".") {
                 Ok(rest) => input = rest,
@@ -47,9 +115,18 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for Field<'s> {
 
         let name = span(initial_input, input);
 
-        let field = scheme
-            .get_field_index(name)
+        #[cfg(feature = "unicode")]
+        let normalized: Cow<'i, str> = match name.nfc().collect::<String>() {
+            ref nfc if nfc == name => Cow::Borrowed(name),
+            nfc => Cow::Owned(nfc),
+        };
+        #[cfg(not(feature = "unicode"))]
+        let normalized: Cow<'i, str> = Cow::Borrowed(name);
+
+        let mut field = scheme
+            .get_field_index(&normalized)
             .map_err(|err| (LexErrorKind::UnknownField(err), name))?;
+        field.span = Span::new(initial_input, name);
 
         Ok((field, input))
     }
@@ -79,20 +156,67 @@ impl<'s> GetType for Field<'s> {
 #[fail(display = "unknown field")]
 pub struct UnknownFieldError;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span_start: usize,
+    pub span_len: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary_label: Label,
+    pub help: Option<String>,
+}
+
+fn help_for_kind(kind: &LexErrorKind) -> Option<String> {
+    match kind {
+        LexErrorKind::UnknownField(_) => {
+            Some("check the field name against the scheme it was parsed with".to_owned())
+        }
+        LexErrorKind::ExpectedName(name) => Some(format!("expected {} here", name)),
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct ParseError<'i> {
     kind: LexErrorKind,
+    source: &'i str,
     input: &'i str,
     line_number: usize,
     span_start: usize,
     span_len: usize,
+    abs_span_start: usize,
+    abs_span_len: usize,
 }
 
 impl<'i> Error for ParseError<'i> {}
 
 impl<'i> ParseError<'i> {
     pub(crate) fn new(mut input: &'i str, (kind, span): (LexErrorKind, &'i str)) -> Self {
+        let source = input;
+
         let mut span_start = span.as_ptr() as usize - input.as_ptr() as usize;
+        let abs_span_start = span_start;
+        let abs_span_len = span.len();
 
         let (line_number, line_start) = input[..span_start]
             .match_indices('\n')
@@ -115,12 +239,67 @@ impl<'i> ParseError<'i> {
 
         ParseError {
             kind,
+            source,
             input,
             line_number,
             span_start,
             span_len,
+            abs_span_start,
+            abs_span_len,
+        }
+    }
+
+    pub fn report(&self) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: self.kind.to_string(),
+            primary_label: Label {
+                span_start: self.abs_span_start,
+                span_len: self.abs_span_len,
+                message: self.kind.to_string(),
+            },
+            help: help_for_kind(&self.kind),
         }
     }
+
+    pub fn render(&self, colored: bool) -> String {
+        let (bold, red, reset) = if colored {
+            ("\u{1b}[1m", "\u{1b}[31m", "\u{1b}[0m")
+        } else {
+            ("", "", "")
+        };
+
+        let lines: Vec<&str> = self.source.split('\n').collect();
+        let first = self.line_number.saturating_sub(1);
+        let last = min(lines.len() - 1, self.line_number + 1);
+
+        let mut out = format!("{}{}error{}: {}\n", bold, red, reset, self.kind);
+        out += &format!(
+            "  --> line {}, column {}\n",
+            self.line_number + 1,
+            self.span_start + 1
+        );
+
+        for (offset, line) in lines[first..=last].iter().enumerate() {
+            let line_number = first + offset;
+            out += &format!("{:>4} | {}\n", line_number + 1, line);
+
+            if line_number == self.line_number {
+                out += "     | ";
+                out += &" ".repeat(self.span_start);
+                out += red;
+                out += &"^".repeat(max(1, self.span_len));
+                out += reset;
+                out += "\n";
+            }
+        }
+
+        if let Some(help) = help_for_kind(&self.kind) {
+            out += &format!("  = help: {}\n", help);
+        }
+
+        out
+    }
 }
 
 impl<'i> Display for ParseError<'i> {
@@ -146,15 +325,182 @@ impl<'i> Display for ParseError<'i> {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    params: Vec<Type>,
+    variadic: Option<Type>,
+    return_type: Type,
+}
+
+impl Signature {
+    pub fn new(params: Vec<Type>, return_type: Type) -> Self {
+        Signature {
+            params,
+            variadic: None,
+            return_type,
+        }
+    }
+
+    pub fn with_variadic(params: Vec<Type>, variadic: Type, return_type: Type) -> Self {
+        Signature {
+            params,
+            variadic: Some(variadic),
+            return_type,
+        }
+    }
+
+    fn arg_type(&self, index: usize) -> Option<Type> {
+        self.params.get(index).cloned().or_else(|| self.variadic.clone())
+    }
+
+    fn accepts_arity(&self, count: usize) -> bool {
+        if self.variadic.is_some() {
+            count >= self.params.len()
+        } else {
+            count == self.params.len()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "unknown function")]
+pub struct UnknownFunctionError;
+
+#[derive(Default)]
+pub(crate) struct FunctionRegistry {
+    functions: IndexMap<String, Signature, FnvBuildHasher>,
+}
+
+impl FunctionRegistry {
+    fn get(&self, name: &str) -> Result<(usize, &Signature), UnknownFunctionError> {
+        self.functions
+            .get_full(name)
+            .map(|(index, _, signature)| (index, signature))
+            .ok_or(UnknownFunctionError)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct FunctionCall<'s> {
+    scheme: &'s Scheme,
+    index: usize,
+    args: Vec<Value<'s>>,
+    span: Span,
+}
+
+impl<'s> FunctionCall<'s> {
+    pub fn function_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn args(&self) -> &[Value<'s>] {
+        &self.args
+    }
+
+    fn signature(&self) -> &'s Signature {
+        self.scheme.functions.functions.get_index(self.index).unwrap().1
+    }
+}
+
+impl<'s> GetType for FunctionCall<'s> {
+    fn get_type(&self) -> Type {
+        self.signature().return_type
+    }
+}
+
+impl<'s> Spanned for FunctionCall<'s> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCall<'s> {
+    fn lex_with(mut input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+        let initial_input = input;
+
+        input = lex_identifier_segment(input)?.1;
+
+        let name = span(initial_input, input);
+
+        #[cfg(feature = "unicode")]
+        let normalized: Cow<'i, str> = match name.nfc().collect::<String>() {
+            ref nfc if nfc == name => Cow::Borrowed(name),
+            nfc => Cow::Owned(nfc),
+        };
+        #[cfg(not(feature = "unicode"))]
+        let normalized: Cow<'i, str> = Cow::Borrowed(name);
+
+        let (index, signature) = scheme
+            .functions
+            .get(&normalized)
+            .map_err(|err| (LexErrorKind::UnknownFunction(err), name))?;
+
+        input = expect(input, "(")?;
+
+        let mut args = Vec::new();
+
+        input = input.trim_start();
+        if expect(input, ")").is_err() {
+            loop {
+                let arg_start = input;
+                let (arg, rest) = Value::lex_with(input, scheme)?;
+
+                if let Some(expected) = signature.arg_type(args.len()) {
+                    let actual = arg.get_type();
+                    if actual != expected {
+                        return Err((
+                            LexErrorKind::TypeMismatch { expected, actual },
+                            span(arg_start, rest),
+                        ));
+                    }
+                }
+
+                args.push(arg);
+                input = rest.trim_start();
+
+                match expect(input, ",") {
+                    Ok(rest) => input = rest.trim_start(),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        if !signature.accepts_arity(args.len()) {
+            return Err((
+                LexErrorKind::ArityMismatch {
+                    expected: signature.params.len(),
+                    actual: args.len(),
+                },
+                name,
+            ));
+        }
+
+        input = expect(input, ")")
+            .map_err(|_| (LexErrorKind::ExpectedName("closing `)`"), input))?;
+
+        Ok((
+            FunctionCall {
+                scheme,
+                index,
+                args,
+                span: Span::new(initial_input, span(initial_input, input)),
+            },
+            input,
+        ))
+    }
+}
+
 #[derive(Default)]
 pub struct Scheme {
     fields: IndexMap<String, Type, FnvBuildHasher>,
+    functions: FunctionRegistry,
 }
 
 impl FromIterator<(String, Type)> for Scheme {
     fn from_iter<I: IntoIterator<Item = (String, Type)>>(iter: I) -> Self {
         Scheme {
             fields: IndexMap::from_iter(iter),
+            functions: FunctionRegistry::default(),
         }
     }
 }
@@ -167,23 +513,72 @@ impl PartialEq for Scheme {
 
 impl Eq for Scheme {}
 
+#[derive(Debug, PartialEq, Fail)]
+#[fail(
+    display = "field {} is already registered with type {:?} (tried to register it with type {:?})",
+    name, existing_type, new_type
+)]
+pub struct DuplicateFieldError {
+    name: String,
+    existing_type: Type,
+    new_type: Type,
+}
+
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "function {} is already registered", name)]
+pub struct DuplicateFunctionError {
+    name: String,
+}
+
+#[derive(Debug, PartialEq, Fail)]
+pub enum SchemeMergeError {
+    #[fail(display = "{}", _0)]
+    Field(DuplicateFieldError),
+    #[fail(display = "{}", _0)]
+    Function(DuplicateFunctionError),
+}
+
+impl From<DuplicateFieldError> for SchemeMergeError {
+    fn from(err: DuplicateFieldError) -> Self {
+        SchemeMergeError::Field(err)
+    }
+}
+
+impl From<DuplicateFunctionError> for SchemeMergeError {
+    fn from(err: DuplicateFunctionError) -> Self {
+        SchemeMergeError::Function(err)
+    }
+}
+
 impl<'s> Scheme {
-    pub fn add_field(&mut self, name: String, ty: Type) {
+    pub fn try_add_field(&mut self, name: String, ty: Type) -> Result<(), DuplicateFieldError> {
+        #[cfg(feature = "unicode")]
+        let name = name.nfc().collect::<String>();
+
         match self.fields.entry(name) {
-            Entry::Occupied(entry) => {
-                panic!("Tried to register field {} with type {:?} but it's already registered with type {:?}", entry.key(), ty, entry.get())
-            }
+            Entry::Occupied(entry) => Err(DuplicateFieldError {
+                name: entry.key().clone(),
+                existing_type: *entry.get(),
+                new_type: ty,
+            }),
             Entry::Vacant(entry) => {
                 entry.insert(ty);
+                Ok(())
             }
         }
     }
 
+    pub fn add_field(&mut self, name: String, ty: Type) {
+        self.try_add_field(name, ty)
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+
     pub(crate) fn get_field_index(&'s self, name: &str) -> Result<Field<'s>, UnknownFieldError> {
         match self.fields.get_full(name) {
             Some((index, ..)) => Ok(Field {
                 scheme: self,
                 index,
+                span: Span::default(),
             }),
             None => Err(UnknownFieldError),
         }
@@ -193,6 +588,88 @@ impl<'s> Scheme {
         self.fields.len()
     }
 
+    pub fn try_add_function(
+        &mut self,
+        name: String,
+        signature: Signature,
+    ) -> Result<(), DuplicateFunctionError> {
+        #[cfg(feature = "unicode")]
+        let name = name.nfc().collect::<String>();
+
+        match self.functions.functions.entry(name) {
+            Entry::Occupied(entry) => Err(DuplicateFunctionError {
+                name: entry.key().clone(),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(signature);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn add_function(&mut self, name: String, signature: Signature) {
+        self.try_add_function(name, signature)
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    pub fn mount(&mut self, prefix: &str, other: Scheme) -> Result<(), SchemeMergeError> {
+        let fields = other
+            .fields
+            .into_iter()
+            .map(|(name, ty)| (format!("{}.{}", prefix, name), ty))
+            .collect();
+        let functions = other
+            .functions
+            .functions
+            .into_iter()
+            .map(|(name, signature)| (format!("{}.{}", prefix, name), signature))
+            .collect();
+
+        self.try_merge(fields, functions)
+    }
+
+    pub fn merge(&mut self, other: Scheme) -> Result<(), SchemeMergeError> {
+        self.try_merge(other.fields, other.functions.functions)
+    }
+
+    fn try_merge(
+        &mut self,
+        fields: IndexMap<String, Type, FnvBuildHasher>,
+        functions: IndexMap<String, Signature, FnvBuildHasher>,
+    ) -> Result<(), SchemeMergeError> {
+        #[cfg(feature = "unicode")]
+        let fields: IndexMap<String, Type, FnvBuildHasher> = fields
+            .into_iter()
+            .map(|(name, ty)| (name.nfc().collect::<String>(), ty))
+            .collect();
+        #[cfg(feature = "unicode")]
+        let functions: IndexMap<String, Signature, FnvBuildHasher> = functions
+            .into_iter()
+            .map(|(name, signature)| (name.nfc().collect::<String>(), signature))
+            .collect();
+
+        for (name, ty) in &fields {
+            if let Some(existing_type) = self.fields.get(name) {
+                return Err(DuplicateFieldError {
+                    name: name.clone(),
+                    existing_type: *existing_type,
+                    new_type: *ty,
+                }.into());
+            }
+        }
+
+        for name in functions.keys() {
+            if self.functions.functions.contains_key(name) {
+                return Err(DuplicateFunctionError { name: name.clone() }.into());
+            }
+        }
+
+        self.fields.extend(fields);
+        self.functions.functions.extend(functions);
+
+        Ok(())
+    }
+
     pub fn parse<'i>(&'s self, input: &'i str) -> Result<Filter<'s>, ParseError<'i>> {
         complete(Filter::lex_with(input.trim(), self)).map_err(|err| ParseError::new(input, err))
     }
@@ -245,3 +722,170 @@ fn test_field() {
         "x.y.z"
     );
 }
+
+#[test]
+fn test_parse_error_report() {
+    let scheme = &[("x", Type::Bytes)]
+        .iter()
+        .map(|&(k, t)| (k.to_owned(), t))
+        .collect();
+
+    let err = scheme.parse("y\n").unwrap_err();
+
+    let report = err.report();
+    assert_eq!(report.severity, Severity::Error);
+    assert_eq!(report.primary_label.span_start, 0);
+    assert_eq!(report.primary_label.span_len, 1);
+
+    let rendered = err.render(false);
+    assert!(rendered.contains("error:"));
+    assert!(rendered.contains("y"));
+    assert!(rendered.contains('^'));
+
+    let colored = err.render(true);
+    assert!(colored.contains("\u{1b}[31m"));
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_unicode_field() {
+    let decomposed = "cafe\u{0301}";
+    let precomposed = "caf\u{e9}";
+    assert_ne!(decomposed, precomposed);
+
+    let mut scheme = Scheme::default();
+    scheme.add_field(decomposed.to_owned(), Type::Bytes);
+
+    assert_ok!(
+        Field::lex_with(precomposed, &scheme),
+        scheme.get_field_index(precomposed).unwrap(),
+        ""
+    );
+
+    assert_err!(
+        Field::lex_with("1abc", &scheme),
+        LexErrorKind::ExpectedName("identifier character"),
+        "1abc"
+    );
+
+    assert_err!(
+        Field::lex_with("\u{0301}abc", &scheme),
+        LexErrorKind::ExpectedName("identifier character"),
+        "\u{0301}abc"
+    );
+}
+
+#[test]
+fn test_function_call() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("http.host".to_owned(), Type::Bytes);
+    scheme.add_function(
+        "lower".to_owned(),
+        Signature::new(vec![Type::Bytes], Type::Bytes),
+    );
+
+    let (call, rest) = FunctionCall::lex_with("lower(http.host);", &scheme).unwrap();
+    assert_eq!(call.function_index(), 0);
+    assert_eq!(call.args().len(), 1);
+    assert_eq!(call.get_type(), Type::Bytes);
+    assert_eq!(rest, ";");
+
+    assert_err!(
+        FunctionCall::lex_with("nonexistent(http.host)", &scheme),
+        LexErrorKind::UnknownFunction(UnknownFunctionError),
+        "nonexistent"
+    );
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_unicode_function_call() {
+    let decomposed = "cafe\u{0301}";
+    let precomposed = "caf\u{e9}";
+    assert_ne!(decomposed, precomposed);
+
+    let mut scheme = Scheme::default();
+    scheme.add_field("http.host".to_owned(), Type::Bytes);
+    scheme.add_function(decomposed.to_owned(), Signature::new(vec![Type::Bytes], Type::Bytes));
+
+    let (call, rest) =
+        FunctionCall::lex_with(&format!("{}(http.host);", precomposed), &scheme).unwrap();
+    assert_eq!(call.function_index(), 0);
+    assert_eq!(rest, ";");
+}
+
+#[test]
+fn test_field_span() {
+    let scheme = &[("x.y.z0", Type::Unsigned)]
+        .iter()
+        .map(|&(k, t)| (k.to_owned(), t))
+        .collect();
+
+    let (field, _) = Field::lex_with("x.y.z0", scheme).unwrap();
+    assert_eq!(field.span(), Span { start: 0, len: 6 });
+
+    let (other, _) = Field::lex_with("x.y.z0;", scheme).unwrap();
+    assert_eq!(field, other);
+    assert_eq!(other.span(), Span { start: 0, len: 6 });
+}
+
+#[test]
+fn test_function_call_span() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("http.host".to_owned(), Type::Bytes);
+    scheme.add_function(
+        "lower".to_owned(),
+        Signature::new(vec![Type::Bytes], Type::Bytes),
+    );
+
+    let (call, rest) = FunctionCall::lex_with("lower(http.host);", &scheme).unwrap();
+    assert_eq!(rest, ";");
+    assert_eq!(call.span(), Span { start: 0, len: 16 });
+}
+
+#[test]
+fn test_mount() {
+    let mut http_scheme = Scheme::default();
+    http_scheme.add_field("host".to_owned(), Type::Bytes);
+
+    let mut scheme = Scheme::default();
+    scheme.add_field("is_TCP".to_owned(), Type::Bool);
+    scheme.mount("http", http_scheme).unwrap();
+
+    assert_ok!(
+        Field::lex_with("http.host;", &scheme),
+        scheme.get_field_index("http.host").unwrap(),
+        ";"
+    );
+}
+
+#[test]
+fn test_merge_conflict() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("x".to_owned(), Type::Bytes);
+
+    let mut other = Scheme::default();
+    other.add_field("x".to_owned(), Type::Unsigned);
+
+    assert_eq!(
+        scheme.merge(other).unwrap_err(),
+        SchemeMergeError::Field(DuplicateFieldError {
+            name: "x".to_owned(),
+            existing_type: Type::Bytes,
+            new_type: Type::Unsigned,
+        })
+    );
+}
+
+#[test]
+fn test_merge_conflict_is_atomic() {
+    let mut scheme = Scheme::default();
+    scheme.add_field("b".to_owned(), Type::Bytes);
+
+    let mut other = Scheme::default();
+    other.add_field("a".to_owned(), Type::Bytes);
+    other.add_field("b".to_owned(), Type::Unsigned);
+
+    assert!(scheme.merge(other).is_err());
+    assert!(scheme.get_field_index("a").is_err());
+}